@@ -29,20 +29,13 @@ impl Pass for ControlFlowSimplification {
         // Build the predecessor table and dominator tree.
         let pt = PredecessorTable::new(unit.dfg(), unit.func_layout());
         let dt = DominatorTree::new(unit.cfg(), unit.func_layout(), &pt);
-        let bn = BlockNumbering::new(unit.dfg(), unit.func_layout());
 
         // Collect the phi instructions. We do this by gathering the values a
         // phi node can produce, and noting which edges lead to this value, then
         // transitively do this for nested phi nodes.
         let mut phi_ways = vec![];
         for block in unit.func_layout().blocks() {
-            let imm_dom = match dt
-                .dominators(block)
-                .iter()
-                .cloned()
-                .filter(|&bb| bb != block)
-                .max_by_key(|&bb| bn[bb])
-            {
+            let imm_dom = match dt.immediate_dominator(block) {
                 Some(bb) => bb,
                 None => continue,
             };
@@ -50,6 +43,16 @@ impl Pass for ControlFlowSimplification {
                 if !unit.dfg()[inst].opcode().is_phi() {
                     continue;
                 }
+                // A phi must live on the dominance frontier of at least one
+                // of its own predecessors, otherwise a single definition
+                // would dominate the block and the phi would be trivial.
+                debug_assert!(
+                    pt.pred(block)
+                        .any(|bb| dt.dominance_frontier(bb).any(|f| f == block)),
+                    "phi {} in {} is not on the dominance frontier of its inputs",
+                    inst.dump(unit.dfg(), unit.try_cfg()),
+                    block.dump(unit.cfg())
+                );
                 let ways = prepare_phi(ctx, unit, block, inst, &pt, imm_dom);
                 phi_ways.push((inst, ways));
             }
@@ -96,10 +99,137 @@ impl Pass for ControlFlowSimplification {
             modified |= true;
         }
 
+        // Merge basic blocks that are connected by a single unconditional
+        // edge, shrinking the CFG before downstream passes see it. Merging
+        // can expose further merge opportunities, so iterate to a fixpoint.
+        loop {
+            let pt = PredecessorTable::new(unit.dfg(), unit.func_layout());
+            let merge = unit
+                .func_layout()
+                .blocks()
+                .find_map(|a| find_merge_candidate(unit, &pt, a));
+            let (a, b) = match merge {
+                Some(pair) => pair,
+                None => break,
+            };
+            trace!(
+                "Merging {} into {}",
+                b.dump(unit.cfg()),
+                a.dump(unit.cfg())
+            );
+            merge_blocks(unit, a, b);
+            modified |= true;
+        }
+
+        // Finally, sweep away any block that the edits above left
+        // unreachable. `BlockNumbering` already performs a reachability
+        // traversal from the entry block, so anything it didn't visit is
+        // provably dead.
+        let bn = BlockNumbering::new(unit.dfg(), unit.func_layout());
+        let dead: Vec<Block> = unit
+            .func_layout()
+            .blocks()
+            .filter(|&block| !bn.is_reachable(block))
+            .collect();
+        for block in dead {
+            trace!("Removing unreachable block {}", block.dump(unit.cfg()));
+            remove_unreachable_block(unit, &bn, block);
+            modified |= true;
+        }
+
         modified
     }
 }
 
+// Remove `block`, which is unreachable: first drop its edge from any phi in
+// a still-reachable successor, then its own instructions (pruning them if
+// now unused), then the block itself.
+fn remove_unreachable_block(unit: &mut impl UnitBuilder, bn: &BlockNumbering, block: Block) {
+    let term = unit.func_layout().terminator(block);
+    let succs: Vec<Block> = unit.dfg()[term].blocks().to_vec();
+    for succ in succs {
+        if succ == block || !bn.is_reachable(succ) {
+            continue;
+        }
+        let phis: Vec<_> = unit
+            .func_layout()
+            .insts(succ)
+            .filter(|&inst| unit.dfg()[inst].opcode().is_phi())
+            .collect();
+        for inst in phis {
+            let data = &mut unit.dfg_mut()[inst];
+            let kept: Vec<(Block, Value)> = data
+                .blocks()
+                .iter()
+                .zip(data.args().iter())
+                .filter(|&(&bb, _)| bb != block)
+                .map(|(&bb, &v)| (bb, v))
+                .collect();
+            *data.blocks_mut() = kept.iter().map(|&(bb, _)| bb).collect();
+            *data.args_mut() = kept.iter().map(|&(_, v)| v).collect();
+        }
+    }
+
+    let insts: Vec<_> = unit.func_layout().insts(block).collect();
+    for inst in insts.into_iter().rev() {
+        unit.func_layout_mut().remove_inst(inst);
+        unit.prune_if_unused(inst);
+    }
+    unit.func_layout_mut().remove_block(block);
+}
+
+// Check whether `a` ends in an unconditional branch to a block `b` that has
+// no other predecessors, in which case `a` and `b` can be merged into one.
+fn find_merge_candidate(
+    unit: &impl UnitBuilder,
+    pt: &PredecessorTable,
+    a: Block,
+) -> Option<(Block, Block)> {
+    let term = unit.func_layout().terminator(a);
+    let data = &unit.dfg()[term];
+    // Never merge across a block that suspends the process; its
+    // `Wait`/`WaitTime` terminator must remain the last thing `a` does.
+    if data.opcode() != Opcode::Br {
+        return None;
+    }
+    let b = data.blocks()[0];
+    if b == a || pt.pred(b).count() != 1 {
+        return None;
+    }
+    Some((a, b))
+}
+
+// Splice `b`'s instructions onto the end of `a` and remove `b`. Since `a` is
+// `b`'s only predecessor, any phi in `b` has exactly one incoming value and
+// is simply replaced by it.
+fn merge_blocks(unit: &mut impl UnitBuilder, a: Block, b: Block) {
+    // Phis in `b` only ever see the single edge from `a`, so fold them away
+    // before splicing in the rest of the block.
+    let phis: Vec<_> = unit
+        .func_layout()
+        .insts(b)
+        .filter(|&inst| unit.dfg()[inst].opcode().is_phi())
+        .collect();
+    for inst in phis {
+        let value = unit.dfg().inst_result(inst);
+        let with = unit.dfg()[inst].args()[0];
+        unit.dfg_mut().replace_use(value, with);
+        unit.prune_if_unused(inst);
+    }
+
+    // Drop `a`'s terminator and move every remaining instruction of `b`
+    // (including its new terminator) onto the end of `a`.
+    let a_term = unit.func_layout().terminator(a);
+    unit.func_layout_mut().remove_inst(a_term);
+    unit.prune_if_unused(a_term);
+    let insts: Vec<_> = unit.func_layout().insts(b).collect();
+    for inst in insts {
+        unit.func_layout_mut().remove_inst(inst);
+        unit.func_layout_mut().append_inst(inst, a);
+    }
+    unit.func_layout_mut().remove_block(b);
+}
+
 // Find the preconditions for the values a phi node can produce. The resulting
 // list may be non-exhaustive in case of difficult phi nodes.
 fn prepare_phi(
@@ -162,12 +292,33 @@ fn justify_edge(
         Opcode::BrCond if data.blocks()[0] == to => Some(Cond::Neg(data.args()[0])),
         Opcode::BrCond if data.blocks()[1] == to => Some(Cond::Pos(data.args()[0])),
 
+        // Multi-way switches contribute an equality condition against the
+        // index of whichever case targets `to`.
+        Opcode::Sw => {
+            let idx = data
+                .blocks()
+                .iter()
+                .position(|&bb| bb == to)
+                .expect("`to` must be a successor of `from`");
+            Some(Cond::Eq(data.args()[0], idx))
+        }
+
         _ => unreachable!("weird terminator found"),
     };
 
+    // The branch condition may itself be computed from other, already
+    // tracked conditions (`not`, `and`/`or`). Expand it into disjunctive
+    // normal form so that each disjunct is a flat conjunction of leaf
+    // conditions on the underlying inputs, just like a plain `BrCond`
+    // would produce.
+    let cond_dnf: Vec<Vec<Cond>> = match cond {
+        Some(cond) => flatten_dnf(&normalize_cond(unit, cond)),
+        None => vec![vec![]],
+    };
+
     // If we have arrived at the target then we are done.
     if from == target {
-        return vec![cond.into_iter().collect()];
+        return cond_dnf;
     }
 
     // Gather the conditions to arrive from each of the predecessors to the from
@@ -178,21 +329,150 @@ fn justify_edge(
         if seen.contains(&bb) {
             continue;
         }
-        for mut route in justify_edge(ctx, unit, bb, from, target, seen, pt) {
-            if let Some(cond) = cond {
-                route.push(cond);
+        for route in justify_edge(ctx, unit, bb, from, target, seen, pt) {
+            for conj in &cond_dnf {
+                let mut combined = route.clone();
+                combined.extend(conj.iter().cloned());
+                routes.push(combined);
             }
-            routes.push(route);
         }
     }
     seen.pop();
     routes
 }
 
+/// A symbolic tree of conditions, used to unfold a branch condition that is
+/// itself computed from other, already-tracked conditions (boolean `not`,
+/// or `and`/`or` of tracked conditions) into the leaves it is ultimately
+/// built from. An equality compare against a constant is left as a leaf on
+/// the compare's own result rather than folded onto the compared operand,
+/// so it will not merge with a `Cond::Eq` contributed by a `Sw` on that
+/// same operand.
+#[derive(Debug, Clone)]
+enum CondExpr {
+    Leaf(Cond),
+    And(Vec<CondExpr>),
+    Or(Vec<CondExpr>),
+}
+
+// Rewrite `cond` in terms of the inputs of the operation that defines its
+// value, when that operation is invertible/foldable. Anything else is left
+// as a leaf.
+fn normalize_cond(unit: &impl UnitBuilder, cond: Cond) -> CondExpr {
+    let (value, positive) = match cond {
+        Cond::Pos(v) => (v, true),
+        Cond::Neg(v) => (v, false),
+        Cond::Eq(..) => return CondExpr::Leaf(cond),
+    };
+    let inst = match unit.dfg().value_inst(value) {
+        Some(inst) => inst,
+        None => return CondExpr::Leaf(cond),
+    };
+    let data = &unit.dfg()[inst];
+    match data.opcode() {
+        // `not(a)` just flips the polarity we are asking about.
+        Opcode::Not => normalize_cond(
+            unit,
+            if positive {
+                Cond::Neg(data.args()[0])
+            } else {
+                Cond::Pos(data.args()[0])
+            },
+        ),
+
+        // `and(a, b, ...)` being true requires every input to be true;
+        // being false means at least one input is false.
+        Opcode::And if positive => CondExpr::And(
+            data.args()
+                .iter()
+                .map(|&a| normalize_cond(unit, Cond::Pos(a)))
+                .collect(),
+        ),
+        Opcode::And => CondExpr::Or(
+            data.args()
+                .iter()
+                .map(|&a| normalize_cond(unit, Cond::Neg(a)))
+                .collect(),
+        ),
+
+        // `or(a, b, ...)` being true means at least one input is true;
+        // being false requires every input to be false.
+        Opcode::Or if positive => CondExpr::Or(
+            data.args()
+                .iter()
+                .map(|&a| normalize_cond(unit, Cond::Pos(a)))
+                .collect(),
+        ),
+        Opcode::Or => CondExpr::And(
+            data.args()
+                .iter()
+                .map(|&a| normalize_cond(unit, Cond::Neg(a)))
+                .collect(),
+        ),
+
+        // Anything else, including an equality compare against a constant,
+        // is left as a leaf on its own result rather than folded onto the
+        // compared operand.
+        _ => CondExpr::Leaf(cond),
+    }
+}
+
+// Flatten a condition tree into disjunctive normal form: a list of
+// conjunctions, any one of which being satisfied makes the whole
+// expression true.
+fn flatten_dnf(expr: &CondExpr) -> Vec<Vec<Cond>> {
+    match expr {
+        CondExpr::Leaf(cond) => vec![vec![*cond]],
+        CondExpr::Or(children) => children.iter().flat_map(flatten_dnf).collect(),
+        CondExpr::And(children) => children.iter().map(flatten_dnf).fold(
+            vec![vec![]],
+            |acc, child_dnf| {
+                acc.iter()
+                    .flat_map(|conj| {
+                        child_dnf.iter().map(move |disjunct| {
+                            let mut conj = conj.clone();
+                            conj.extend(disjunct.iter().cloned());
+                            conj
+                        })
+                    })
+                    .collect()
+            },
+        ),
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum Cond {
+pub(crate) enum Cond {
     Pos(Value),
     Neg(Value),
+    /// The value equals the given case index of a multi-way switch.
+    Eq(Value, usize),
+}
+
+/// How a candidate discriminator value is used across a set of ways: either
+/// as a plain boolean (`Pos`/`Neg`), or as the selector of a multi-valued
+/// switch, where `arity` is one more than the highest case index seen.
+#[derive(Debug, Clone, Copy)]
+enum DiscKind {
+    Bool { uses: usize, imbalance: isize },
+    Switch { uses: usize, arity: usize },
+}
+
+impl DiscKind {
+    fn uses(&self) -> usize {
+        match *self {
+            DiscKind::Bool { uses, .. } | DiscKind::Switch { uses, .. } => uses,
+        }
+    }
+
+    // Secondary sort key once `uses` is tied: for booleans prefer the more
+    // balanced split; switches have no such notion.
+    fn tie_break(&self) -> isize {
+        match *self {
+            DiscKind::Bool { imbalance, .. } => -imbalance.abs(),
+            DiscKind::Switch { .. } => 0,
+        }
+    }
 }
 
 fn build_discriminator(
@@ -208,46 +488,90 @@ fn build_discriminator(
     }
 
     // Find the largest discriminating factor of each way.
-    let mut table = HashMap::<Value, (usize, isize)>::new();
+    let mut table = HashMap::<Value, DiscKind>::new();
     for (_, conds) in ways {
         for &cond in conds {
-            let (v, tick) = match cond {
-                Cond::Pos(v) => (v, 1),
-                Cond::Neg(v) => (v, -1),
-            };
-            let e = table.entry(v).or_insert((0, 0));
-            e.0 += 1;
-            e.1 += tick;
+            match cond {
+                Cond::Pos(v) | Cond::Neg(v) => {
+                    let tick = if let Cond::Pos(_) = cond { 1 } else { -1 };
+                    match table.entry(v).or_insert(DiscKind::Bool {
+                        uses: 0,
+                        imbalance: 0,
+                    }) {
+                        DiscKind::Bool { uses, imbalance } => {
+                            *uses += 1;
+                            *imbalance += tick;
+                        }
+                        DiscKind::Switch { .. } => unreachable!("value used as both bool and switch"),
+                    }
+                }
+                Cond::Eq(v, idx) => {
+                    match table
+                        .entry(v)
+                        .or_insert(DiscKind::Switch { uses: 0, arity: 0 })
+                    {
+                        DiscKind::Switch { uses, arity } => {
+                            *uses += 1;
+                            *arity = (*arity).max(idx + 1);
+                        }
+                        DiscKind::Bool { .. } => unreachable!("value used as both bool and switch"),
+                    }
+                }
+            }
         }
     }
-    let (disc, (_uses, _imbalance)) = table
+    let (disc, kind) = table
         .into_iter()
-        .map(|(v, (n, tick))| (v, (n, -tick.abs())))
-        .max_by_key(|&(_, x)| x)
+        .max_by_key(|(_, kind)| (kind.uses(), kind.tie_break()))
         .expect("some discriminator must be present");
     trace!("    Discriminator is {} ({})", disc, disc.dump(unit.dfg()));
 
-    // Split the ways over the discriminator.
-    let mux_conds = [Cond::Neg(disc), Cond::Pos(disc)];
-    let mux_values: Vec<_> = mux_conds
+    // Split the ways over the discriminator. A plain boolean still
+    // recursively bisects into a 2-way mux; a switch operand is resolved
+    // directly into an n-way mux indexed by the operand itself. Either
+    // polarity of a bool, or a case of a switch, may not actually be
+    // covered by any of `ways` (its arm never reaches this join at all),
+    // so build mux arms only for the conditions that do occur and
+    // backfill the rest with a don't-care value — building an arm for an
+    // uncovered condition would recurse on an empty way list and panic.
+    let mux_at = |cond: Cond| -> Value {
+        let mux_ways: Vec<_> = ways
+            .iter()
+            .flat_map(|(v, conds)| -> Option<(Value, Vec<Cond>)> {
+                if conds.contains(&cond) {
+                    Some((*v, conds.iter().cloned().filter(|&c| c != cond).collect()))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        trace!("      {:?}: {:?}", cond, mux_ways);
+        build_discriminator(ctx, unit, &mux_ways)
+    };
+    let conds: Vec<Cond> = match kind {
+        DiscKind::Bool { .. } => vec![Cond::Neg(disc), Cond::Pos(disc)],
+        DiscKind::Switch { arity, .. } => (0..arity).map(|idx| Cond::Eq(disc, idx)).collect(),
+    };
+    let values: Vec<Option<Value>> = conds
         .iter()
         .map(|&cond| {
-            let mux_ways: Vec<_> = ways
-                .iter()
-                .flat_map(|(v, conds)| -> Option<(Value, Vec<Cond>)> {
-                    if conds.contains(&cond) {
-                        Some((*v, conds.iter().cloned().filter(|&c| c != cond).collect()))
-                    } else {
-                        None
-                    }
-                })
-                .collect();
-            trace!("      {:?}: {:?}", cond, mux_ways);
-            build_discriminator(ctx, unit, &mux_ways)
+            if ways.iter().any(|(_, conds)| conds.contains(&cond)) {
+                Some(mux_at(cond))
+            } else {
+                None
+            }
         })
         .collect();
-
-    // Build the multiplexer which picks among the values.
+    let filler = values
+        .iter()
+        .find_map(|&v| v)
+        .expect("some discriminator case must be present");
+    let mux_values: Vec<Value> = values.into_iter().map(|v| v.unwrap_or(filler)).collect();
+
+    // Build the multiplexer which picks among the values. For a boolean
+    // discriminator the array has two entries and is indexed by the value
+    // itself; for a switch it has one entry per case index up to the
+    // highest one that reaches this join, and is indexed the same way.
     let arr = unit.ins().array(mux_values);
     let mux = unit.ins().mux(arr, disc);
     mux
@@ -323,6 +647,12 @@ impl BlockNumbering {
     pub fn order_slice(&self) -> &[Block] {
         &self.order
     }
+
+    /// Check whether a block is reachable from the entry block. Any block
+    /// not covered by the reachability traversal in `new` is dead code.
+    pub fn is_reachable(&self, block: Block) -> bool {
+        self.numbers.contains_key(&block)
+    }
 }
 
 impl Index<Block> for BlockNumbering {
@@ -330,4 +660,5 @@ impl Index<Block> for BlockNumbering {
     fn index(&self, idx: Block) -> &usize {
         &self.numbers[&idx]
     }
-}
\ No newline at end of file
+}
+
@@ -0,0 +1,165 @@
+// Copyright (c) 2017-2019 Fabian Schuiki
+
+//! Control-flow analyses shared by `ControlFlowSimplification` and
+//! `JumpThreading`: a predecessor table and a dominator tree.
+
+use crate::ir::prelude::*;
+use crate::ir::{DataFlowGraph, FunctionLayout};
+use std::collections::{HashMap, HashSet};
+
+/// A table of the predecessors of every block in a unit.
+pub struct PredecessorTable {
+    preds: HashMap<Block, Vec<Block>>,
+}
+
+impl PredecessorTable {
+    /// Compute the predecessor table of a unit.
+    pub fn new(dfg: &DataFlowGraph, layout: &FunctionLayout) -> Self {
+        let mut preds = HashMap::<Block, Vec<Block>>::new();
+        for block in layout.blocks() {
+            let term = layout.terminator(block);
+            if !dfg[term].opcode().is_terminator() {
+                continue;
+            }
+            for succ in dfg[term].blocks().iter().cloned() {
+                preds.entry(succ).or_insert_with(Vec::new).push(block);
+            }
+        }
+        PredecessorTable { preds }
+    }
+
+    /// Get the predecessors of a block.
+    pub fn pred(&self, block: Block) -> impl Iterator<Item = Block> + '_ {
+        self.preds
+            .get(&block)
+            .into_iter()
+            .flat_map(|preds| preds.iter().cloned())
+    }
+}
+
+/// The dominator tree of a unit.
+///
+/// Besides the usual dominator-set and immediate-dominator queries, this
+/// also exposes each block's dominance frontier — the standard primitive
+/// for deciding where phi nodes must live — computed once up front via the
+/// Cytron/Ferrante algorithm so that every pass built on top of the
+/// dominator tree (CFS, future SSA construction/deconstruction, ...) can
+/// reuse it instead of recomputing its own.
+pub struct DominatorTree {
+    doms: HashMap<Block, HashSet<Block>>,
+    idom: HashMap<Block, Block>,
+    frontiers: HashMap<Block, HashSet<Block>>,
+}
+
+impl DominatorTree {
+    /// Compute the dominator tree of a unit.
+    pub fn new(_cfg: &ControlFlowGraph, layout: &FunctionLayout, pt: &PredecessorTable) -> Self {
+        let entry = layout.entry();
+        let blocks: Vec<Block> = layout.blocks().collect();
+        let all: HashSet<Block> = blocks.iter().cloned().collect();
+
+        // Standard iterative dominator-set computation:
+        // dom(entry) = {entry}; dom(b) = {b} ∪ ⋂ dom(p) for p ∈ pred(b).
+        let mut doms = HashMap::<Block, HashSet<Block>>::new();
+        doms.insert(entry, std::iter::once(entry).collect());
+        for &block in &blocks {
+            if block != entry {
+                doms.insert(block, all.clone());
+            }
+        }
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &block in &blocks {
+                if block == entry {
+                    continue;
+                }
+                let mut new_dom: Option<HashSet<Block>> = None;
+                for p in pt.pred(block) {
+                    new_dom = Some(match new_dom {
+                        Some(acc) => acc.intersection(&doms[&p]).cloned().collect(),
+                        None => doms[&p].clone(),
+                    });
+                }
+                let mut new_dom = new_dom.unwrap_or_default();
+                new_dom.insert(block);
+                if new_dom != doms[&block] {
+                    doms.insert(block, new_dom);
+                    changed = true;
+                }
+            }
+        }
+
+        // The immediate dominator of `b` is the strict dominator closest to
+        // `b`, i.e. the one with the largest dominator set of its own.
+        let mut idom = HashMap::<Block, Block>::new();
+        for &block in &blocks {
+            if let Some(&d) = doms[&block]
+                .iter()
+                .filter(|&&d| d != block)
+                .max_by_key(|&&d| doms[&d].len())
+            {
+                idom.insert(block, d);
+            }
+        }
+
+        let frontiers = dominance_frontiers(&blocks, pt, &idom);
+
+        DominatorTree {
+            doms,
+            idom,
+            frontiers,
+        }
+    }
+
+    /// Get the set of blocks that dominate `block`, including `block`
+    /// itself.
+    pub fn dominators(&self, block: Block) -> &HashSet<Block> {
+        &self.doms[&block]
+    }
+
+    /// Get the immediate dominator of `block`, if any (the entry block has
+    /// none).
+    pub fn immediate_dominator(&self, block: Block) -> Option<Block> {
+        self.idom.get(&block).cloned()
+    }
+
+    /// Get the dominance frontier of `block`: the set of blocks `block`
+    /// dominates a predecessor of, without strictly dominating the block
+    /// itself.
+    pub fn dominance_frontier(&self, block: Block) -> impl Iterator<Item = Block> + '_ {
+        self.frontiers
+            .get(&block)
+            .into_iter()
+            .flat_map(|set| set.iter().cloned())
+    }
+}
+
+// For each block `b` with two or more predecessors, walk from each
+// predecessor up the immediate-dominator chain until (but not past) `b`'s
+// immediate dominator, adding `b` to the frontier of every block visited
+// along the way.
+fn dominance_frontiers(
+    blocks: &[Block],
+    pt: &PredecessorTable,
+    idom: &HashMap<Block, Block>,
+) -> HashMap<Block, HashSet<Block>> {
+    let mut frontiers = HashMap::<Block, HashSet<Block>>::new();
+    for &block in blocks {
+        if pt.pred(block).count() < 2 {
+            continue;
+        }
+        let block_idom = idom.get(&block).cloned();
+        for pred in pt.pred(block) {
+            let mut runner = Some(pred);
+            while let Some(bb) = runner {
+                if Some(bb) == block_idom {
+                    break;
+                }
+                frontiers.entry(bb).or_insert_with(HashSet::new).insert(block);
+                runner = idom.get(&bb).cloned();
+            }
+        }
+    }
+    frontiers
+}
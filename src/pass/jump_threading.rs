@@ -0,0 +1,349 @@
+// Copyright (c) 2017-2019 Fabian Schuiki
+
+//! Jump Threading
+
+use crate::ir::prelude::*;
+use crate::opt::prelude::*;
+use crate::pass::cfs::Cond;
+use crate::pass::gcse::PredecessorTable;
+
+/// How deep the backward search is allowed to walk before giving up on a
+/// `BrCond`. This bounds the cost of the analysis on irreducible or very
+/// large graphs.
+const MAX_THREADING_DEPTH: usize = 32;
+
+/// Jump Threading
+///
+/// This pass looks for `BrCond` terminators whose condition is already
+/// provably fixed along some incoming path by an earlier branch on the same
+/// value, and redirects that earlier branch to jump straight past the
+/// redundant check. Blocks on the threaded path that other predecessors
+/// also enter are duplicated so that those predecessors are left untouched.
+pub struct JumpThreading;
+
+impl Pass for JumpThreading {
+    fn run_on_cfg(ctx: &PassContext, unit: &mut impl UnitBuilder) -> bool {
+        info!("JumpThreading [{}]", unit.unit().name());
+        let mut modified = false;
+
+        // Seed a threading opportunity for each arm of every `BrCond` in the
+        // unit, then thread and apply them one at a time. Applying a thread
+        // can change the CFG out from under later opportunities, so we
+        // recompute the predecessor table before each attempt.
+        loop {
+            let pt = PredecessorTable::new(unit.dfg(), unit.func_layout());
+            let opp = unit
+                .func_layout()
+                .blocks()
+                .find_map(|block| find_opportunity(ctx, unit, &pt, block));
+            let opp = match opp {
+                Some(opp) => opp,
+                None => break,
+            };
+            trace!("Threading {:?}", opp);
+            apply_opportunity(unit, &pt, opp);
+            modified = true;
+        }
+
+        modified
+    }
+}
+
+/// A discovered opportunity to thread a branch: `ancestor`'s edge that
+/// currently leads into `chain[0]` can instead lead into a copy of `chain`
+/// whose last block (a copy of the original `BrCond`) jumps straight to
+/// `target` instead of re-evaluating the condition. `chain` always ends
+/// with the block holding the redundant `BrCond`, in control-flow order.
+#[derive(Debug, Clone)]
+struct ThreadingOpportunity {
+    ancestor: Block,
+    cond: Cond,
+    chain: Vec<Block>,
+    target: Block,
+}
+
+// Look for a `BrCond` in `block` whose condition is already decided by an
+// earlier branch on the same value, reachable backwards via `Br`-only hops.
+fn find_opportunity(
+    _ctx: &PassContext,
+    unit: &impl UnitBuilder,
+    pt: &PredecessorTable,
+    block: Block,
+) -> Option<ThreadingOpportunity> {
+    let term = unit.func_layout().terminator(block);
+    let data = &unit.dfg()[term];
+    if data.opcode() != Opcode::BrCond {
+        return None;
+    }
+    let value = data.args()[0];
+    let false_target = data.blocks()[0];
+    let true_target = data.blocks()[1];
+
+    for &(cond, target) in &[
+        (Cond::Neg(value), false_target),
+        (Cond::Pos(value), true_target),
+    ] {
+        let mut hops_before = vec![];
+        let mut seen = vec![block];
+        if let Some((ancestor, chain)) = walk_back(
+            unit,
+            pt,
+            block,
+            cond,
+            value,
+            &mut hops_before,
+            &mut seen,
+            MAX_THREADING_DEPTH,
+        ) {
+            return Some(ThreadingOpportunity {
+                ancestor,
+                cond,
+                chain,
+                target,
+            });
+        }
+    }
+    None
+}
+
+// Walk backwards from `via`, crossing only `Br` terminators, looking for a
+// predecessor whose terminator already establishes `cond` on `value`.
+// Returns the ancestor block together with the chain of blocks (in
+// control-flow order) from right after it through to (and including) the
+// original `BrCond` block. `seen` tracks blocks already visited along the
+// current path, mirroring `cfs.rs::justify_edge`'s use of its own `seen`
+// list, so that a loop header reachable both from outside the loop and via
+// a `Br`-only back edge does not get walked through more than once.
+fn walk_back(
+    unit: &impl UnitBuilder,
+    pt: &PredecessorTable,
+    via: Block,
+    cond: Cond,
+    value: Value,
+    hops_before: &mut Vec<Block>,
+    seen: &mut Vec<Block>,
+    depth_left: usize,
+) -> Option<(Block, Vec<Block>)> {
+    if depth_left == 0 {
+        return None;
+    }
+
+    for pred in pt.pred(via) {
+        if seen.contains(&pred) {
+            continue;
+        }
+
+        // Never duplicate a block that suspends the process; its side
+        // effects must happen at most once.
+        let pred_term = unit.func_layout().terminator(pred);
+        let pred_data = &unit.dfg()[pred_term];
+        match pred_data.opcode() {
+            Opcode::Wait | Opcode::WaitTime => continue,
+
+            // An earlier branch on the same value: check whether the
+            // edge it takes towards `via` matches the polarity we need.
+            Opcode::BrCond if pred_data.args()[0] == value => {
+                let edge = if pred_data.blocks()[0] == via {
+                    Cond::Neg(value)
+                } else if pred_data.blocks()[1] == via {
+                    Cond::Pos(value)
+                } else {
+                    continue;
+                };
+                if edge == cond {
+                    // `via` plus the hops discovered further down the
+                    // recursion (nearest the original `BrCond` first) make
+                    // up the full chain, in control-flow order.
+                    let mut chain = vec![via];
+                    chain.extend(hops_before.iter().rev().cloned());
+                    return Some((pred, chain));
+                }
+            }
+
+            // Plain `Br`: keep walking further back through the single
+            // predecessor. `via` sits between `pred` and the original
+            // `BrCond`, so remember it as a hop we may need to duplicate.
+            Opcode::Br => {
+                hops_before.push(via);
+                seen.push(pred);
+                if let Some(result) =
+                    walk_back(unit, pt, pred, cond, value, hops_before, seen, depth_left - 1)
+                {
+                    return Some(result);
+                }
+                seen.pop();
+                hops_before.pop();
+            }
+
+            _ => continue,
+        }
+    }
+    None
+}
+
+// Apply a threading opportunity: redirect `opp.ancestor`'s edge through a
+// (possibly duplicated) copy of `opp.chain`, and collapse the chain's last
+// block — a copy of the original `BrCond` — into an unconditional jump to
+// `opp.target`.
+fn apply_opportunity(unit: &mut impl UnitBuilder, pt: &PredecessorTable, opp: ThreadingOpportunity) {
+    let mut rewrite_from = opp.ancestor;
+    let mut old_to = opp.chain[0];
+    let last = opp.chain.len() - 1;
+
+    // Whether an earlier hop in the chain has already been duplicated.
+    // `clone_block` copies a hop's own terminator verbatim, so once a hop
+    // is duplicated its clone keeps pointing at the next hop in addition
+    // to the untouched original — the next hop gains a second, permanent
+    // predecessor even if `pt`, a pre-edit snapshot, says it only has one.
+    // Every hop after the first fork must therefore be treated as shared
+    // too, regardless of what `pt` reports for it.
+    let mut forked = false;
+
+    for (i, &block) in opp.chain.iter().enumerate() {
+        // Blocks with more than one predecessor are shared with paths that
+        // must not be threaded, so duplicate them (fixing up their phis)
+        // before rewriting.
+        let shared = forked || pt.pred(block).count() > 1;
+        let target_block = if shared {
+            duplicate_block(unit, block, rewrite_from)
+        } else {
+            block
+        };
+        redirect_edge(unit, rewrite_from, old_to, target_block, opp.cond);
+
+        if i == last {
+            // `target_block` is the (possibly duplicated) `BrCond` we are
+            // bypassing. Its condition is now statically known, so replace
+            // it with an unconditional branch to `opp.target`.
+            collapse_brcond(unit, target_block, opp.target);
+            if shared {
+                // Duplicating introduced a genuinely new predecessor of
+                // `opp.target`; give any phi there the value it would have
+                // seen along the original edge from `block`.
+                thread_phi_edges(unit, opp.target, block, target_block);
+            }
+        } else {
+            let next = opp.chain[i + 1];
+            if shared {
+                // Duplicating `block` creates a genuinely new, permanent
+                // predecessor of `next` too — not just of the final edge
+                // into `opp.target` — so give any phi there the same
+                // treatment before `next` is itself processed (and
+                // possibly duplicated) on the following iteration.
+                thread_phi_edges(unit, next, block, target_block);
+            }
+            old_to = next;
+        }
+        forked |= shared;
+        rewrite_from = target_block;
+    }
+}
+
+// Duplicate `block`, whose single meaningful predecessor along the threaded
+// path is `incoming_pred`. In the new copy, every phi is replaced by the
+// value that predecessor supplies; in `block` itself, since that edge now
+// arrives via the copy instead, every phi drops its entry for it.
+fn duplicate_block(unit: &mut impl UnitBuilder, block: Block, incoming_pred: Block) -> Block {
+    let clone = unit.dfg_mut().clone_block(block, unit.func_layout_mut());
+
+    let orig_phis: Vec<_> = unit
+        .func_layout()
+        .insts(block)
+        .filter(|&inst| unit.dfg()[inst].opcode().is_phi())
+        .collect();
+    let clone_phis: Vec<_> = unit
+        .func_layout()
+        .insts(clone)
+        .filter(|&inst| unit.dfg()[inst].opcode().is_phi())
+        .collect();
+    for (&orig, &dup) in orig_phis.iter().zip(clone_phis.iter()) {
+        let value = phi_value_for_pred(unit, orig, incoming_pred)
+            .expect("threaded edge must be a real predecessor of the duplicated block");
+        let dup_result = unit.dfg().inst_result(dup);
+        unit.dfg_mut().replace_use(dup_result, value);
+        unit.prune_if_unused(dup);
+        remove_phi_incoming(unit, orig, incoming_pred);
+    }
+
+    clone
+}
+
+// Collapse a `BrCond` we have statically resolved into an unconditional
+// jump to `target` by replacing it with a plain `br`.
+fn collapse_brcond(unit: &mut impl UnitBuilder, block: Block, target: Block) {
+    let term = unit.func_layout().terminator(block);
+    debug_assert_eq!(unit.dfg()[term].opcode(), Opcode::BrCond);
+    unit.insert_before(term);
+    unit.ins().br(target);
+    unit.func_layout_mut().remove_inst(term);
+    unit.prune_if_unused(term);
+}
+
+// Give every phi in `succ` an incoming entry for the new predecessor
+// `new_pred`, using the same value it already associates with `existing_pred`.
+fn thread_phi_edges(unit: &mut impl UnitBuilder, succ: Block, existing_pred: Block, new_pred: Block) {
+    let phis: Vec<_> = unit
+        .func_layout()
+        .insts(succ)
+        .filter(|&inst| unit.dfg()[inst].opcode().is_phi())
+        .collect();
+    for inst in phis {
+        if let Some(value) = phi_value_for_pred(unit, inst, existing_pred) {
+            add_phi_incoming(unit, inst, new_pred, value);
+        }
+    }
+}
+
+// Find the value a phi associates with a given incoming block.
+fn phi_value_for_pred(unit: &impl UnitBuilder, inst: Inst, pred: Block) -> Option<Value> {
+    let data = &unit.dfg()[inst];
+    data.blocks()
+        .iter()
+        .zip(data.args().iter())
+        .find(|&(&bb, _)| bb == pred)
+        .map(|(_, &v)| v)
+}
+
+// Drop a phi's incoming entry for `pred`.
+fn remove_phi_incoming(unit: &mut impl UnitBuilder, inst: Inst, pred: Block) {
+    let data = &mut unit.dfg_mut()[inst];
+    let kept: Vec<(Block, Value)> = data
+        .blocks()
+        .iter()
+        .zip(data.args().iter())
+        .filter(|&(&bb, _)| bb != pred)
+        .map(|(&bb, &v)| (bb, v))
+        .collect();
+    *data.blocks_mut() = kept.iter().map(|&(bb, _)| bb).collect();
+    *data.args_mut() = kept.iter().map(|&(_, v)| v).collect();
+}
+
+// Add a new incoming entry to a phi.
+fn add_phi_incoming(unit: &mut impl UnitBuilder, inst: Inst, pred: Block, value: Value) {
+    let data = &mut unit.dfg_mut()[inst];
+    data.blocks_mut().push(pred);
+    data.args_mut().push(value);
+}
+
+// Rewrite `from`'s terminator so that the edge matching `cond` points at
+// `new_to` instead of `old_to`.
+fn redirect_edge(unit: &mut impl UnitBuilder, from: Block, old_to: Block, new_to: Block, cond: Cond) {
+    let term = unit.func_layout().terminator(from);
+    let data = &mut unit.dfg_mut()[term];
+    match data.opcode() {
+        Opcode::Br => {
+            debug_assert_eq!(data.blocks()[0], old_to);
+            data.blocks_mut()[0] = new_to;
+        }
+        Opcode::BrCond => {
+            let idx = match cond {
+                Cond::Neg(_) => 0,
+                Cond::Pos(_) => 1,
+                Cond::Eq(..) => unreachable!("jump threading only deals with BrCond, never switches"),
+            };
+            debug_assert_eq!(data.blocks()[idx], old_to);
+            data.blocks_mut()[idx] = new_to;
+        }
+        _ => unreachable!("non-branching terminator cannot be threaded"),
+    }
+}